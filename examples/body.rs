@@ -25,7 +25,10 @@ fn main() {
                 employee.rate = rate;
                 warp::reply::json(employee)
             })
-            .or_else(|_| warp::reply::client_error())
+            // `err` is now a `warp::body::BodyError`, which knows the
+            // status it should be reported as (400 for a bad payload,
+            // 500 if something went wrong reading the body).
+            .or_else(|err| warp::reply::client_error().with_status(err.status()))
         });
 
     // POST /employees/:rate  {"name":"Sean","rate":2}
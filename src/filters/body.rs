@@ -12,17 +12,24 @@
 //! a.and(b)
 //! ```
 
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Read};
 use std::marker::PhantomData;
 
 use futures::{Async, Future, Poll, Stream};
 use futures::stream::Concat2;
-use hyper::{Body, Chunk};
+use hyper::{Body, Chunk, StatusCode};
+use hyper::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HeaderMap};
+use libflate::non_blocking::deflate;
+use libflate::non_blocking::gzip;
 use serde::de::DeserializeOwned;
 use serde_json;
+use serde_urlencoded;
 
 use ::filter::{Cons, Filter, filter_fn_cons};
 use ::route;
-use ::Error;
 
 /// Returns a `Filter` that matches any request and extracts a
 /// `Future` of a concatenated body.
@@ -37,31 +44,493 @@ pub fn concat() -> impl Filter<Extract=Cons<ConcatFut>> + Copy {
     })
 }
 
-/// Returns a `Filter` that matches any request and extracts a
-/// `Future` of a JSON-decoded body.
+/// Returns a `Filter` that matches requests whose `Content-Type` is
+/// `application/json` (or a `+json` suffix, e.g. `application/ld+json`),
+/// and extracts a `Future` of a JSON-decoded body.
+///
+/// Requests with a different (or missing) `Content-Type` are declined,
+/// so another branch in an `or`-chain can handle them.
 pub fn json<T: DeserializeOwned>() -> impl Filter<Extract=Cons<JsonFut<T>>> + Copy {
-    concat()
+    concat_if(is_json_mime, "application/json")
         .map(|concat| JsonFut {
             concat,
             _marker: PhantomData,
         })
 }
 
+/// Returns a `Filter` that matches requests whose `Content-Type` is
+/// `application/x-www-form-urlencoded`, and extracts a `Future` of a
+/// urlencoded-form-decoded body.
+///
+/// Requests with a different (or missing) `Content-Type` are declined,
+/// so another branch in an `or`-chain can handle them.
+pub fn form<T: DeserializeOwned>() -> impl Filter<Extract=Cons<FormFut<T>>> + Copy {
+    concat_if(is_form_mime, "application/x-www-form-urlencoded")
+        .map(|concat| FormFut {
+            concat,
+            _marker: PhantomData,
+        })
+}
+
+/// `concat()`, but declining the request up front unless its `Content-Type`
+/// matches `predicate`. Shared by `json()` and `form()` so each only has
+/// to supply its own predicate, not reimplement the body-taking dance.
+fn concat_if<F>(predicate: F, what: &'static str) -> impl Filter<Extract=Cons<ConcatFut>> + Copy
+where
+    F: Fn(&str) -> bool + Copy,
+{
+    filter_fn_cons(move || {
+        route::with(move |route| {
+            if !matches_content_type(route.headers(), predicate) {
+                debug!("request content-type doesn't match {}", what);
+                return None;
+            }
+            route.take_body()
+                .map(|body| ConcatFut {
+                    fut: body.unwrap().concat2(),
+                })
+        })
+    })
+}
+
+/// Returns a `Filter` that matches requests whose `Content-Type` is `mime`
+/// (ignoring any `; charset=...`-style parameters), and declines
+/// otherwise so another branch in an `or`-chain can handle them.
+///
+/// This is a standalone version of the same check `json()` and `form()`
+/// use internally, for composing a custom body extractor out of a
+/// content-type guard and your own decoder.
+pub fn content_type(mime: &'static str) -> impl Filter<Extract=Cons<()>> + Copy {
+    filter_fn_cons(move || {
+        route::with(|route| {
+            if matches_content_type(route.headers(), |essence| essence.eq_ignore_ascii_case(mime)) {
+                Some(())
+            } else {
+                debug!("content-type doesn't match {:?}", mime);
+                None
+            }
+        })
+    })
+}
+
+/// Returns whether the `Content-Type` header's essence (everything before
+/// the first `;`) matches `predicate`. A missing or unparsable header
+/// never matches.
+fn matches_content_type<F>(headers: &HeaderMap, predicate: F) -> bool
+where
+    F: Fn(&str) -> bool,
+{
+    headers.get(CONTENT_TYPE)
+        .and_then(|val| val.to_str().ok())
+        .map(|val| predicate(val.split(';').next().unwrap_or("").trim()))
+        .unwrap_or(false)
+}
+
+fn is_json_mime(essence: &str) -> bool {
+    essence.eq_ignore_ascii_case("application/json") ||
+        essence.to_ascii_lowercase().ends_with("+json")
+}
+
+fn is_form_mime(essence: &str) -> bool {
+    essence.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+}
+
+/// Returns a `Filter` that matches any request and extracts a
+/// `Future` of the concatenated body, as a `Vec<u8>`.
+pub fn bytes() -> impl Filter<Extract=Cons<BytesFut>> + Copy {
+    concat()
+        .map(|concat| BytesFut {
+            concat,
+        })
+}
+
+/// Returns a `Filter` that matches any request and extracts the raw,
+/// un-concatenated body as a `Stream` of `Chunk`s.
+///
+/// Unlike `concat()`, this does not buffer the entire body into memory
+/// before the filter resolves, making it suitable for streaming large
+/// uploads directly to their destination.
+pub fn stream() -> impl Filter<Extract=Cons<Body>> + Copy {
+    filter_fn_cons(move || {
+        route::with(|route| {
+            route.take_body()
+                .map(|body| body.unwrap())
+        })
+    })
+}
+
+/// Returns a `Filter` that matches any request and extracts a `Future` of
+/// the concatenated body, same as `concat()`, but failing with
+/// `BodyError::TooLarge` if the body is bigger than `limit` bytes.
+///
+/// A declared `Content-Length` bigger than `limit` is rejected up front,
+/// before any of the body is read. A chunked-encoded body (no, or a
+/// lying, `Content-Length`) is instead bounded as it streams in, so it
+/// can't buffer more than `limit` bytes in memory regardless of what the
+/// header claimed:
+///
+/// ```
+/// let upload = warp::body::content_length_limit(1024 * 16);
+/// ```
+pub fn content_length_limit(limit: u64) -> impl Filter<Extract=Cons<ConcatFut<LimitedBody>>> + Copy {
+    filter_fn_cons(move || {
+        route::with(|route| {
+            let declared = route.headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|val| val.to_str().ok())
+                .and_then(|val| val.parse::<u64>().ok());
+
+            let too_large = declared.map(|len| len > limit).unwrap_or(false);
+            if too_large {
+                debug!("content-length ({:?}) exceeds limit ({})", declared, limit);
+            }
+
+            route.take_body()
+                .map(|body| ConcatFut {
+                    fut: LimitedBody {
+                        body: body.unwrap(),
+                        read: 0,
+                        limit,
+                        too_large,
+                    }.concat2(),
+                })
+        })
+    })
+}
+
+/// An error that can occur while extracting or decoding a request body.
+///
+/// Unlike a generic `warp::Error`, a `BodyError` knows which HTTP status
+/// it should be reported as, via `BodyError::status()`. This crate slice
+/// has no rejection/auto-conversion plumbing to act on that, though, so a
+/// failed `json()`/`form()` is *not* automatically turned into a response
+/// — a handler must still inspect the error and build a response from
+/// `BodyError::status()` itself, as `examples/body.rs` does.
+#[derive(Debug)]
+pub enum BodyError {
+    /// The body's `Content-Length` (or the body itself) exceeded a
+    /// `content_length_limit()`.
+    TooLarge,
+    /// Reading the body from the connection failed.
+    Io(::hyper::Error),
+    /// The body could not be deserialized into the requested type.
+    Deserialize(Box<StdError + Send + Sync>),
+}
+
+impl BodyError {
+    /// Returns the HTTP status code this error should be reported as.
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            BodyError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            BodyError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            BodyError::Deserialize(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BodyError::TooLarge => f.write_str("request body exceeded its length limit"),
+            BodyError::Io(ref e) => write!(f, "request body error: {}", e),
+            BodyError::Deserialize(ref e) => write!(f, "request body deserialize error: {}", e),
+        }
+    }
+}
+
+impl StdError for BodyError {
+    fn description(&self) -> &str {
+        "request body error"
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        match *self {
+            BodyError::Io(ref e) => Some(e),
+            BodyError::Deserialize(ref e) => Some(&**e),
+            BodyError::TooLarge => None,
+        }
+    }
+}
+
+/// Returns a `Filter` that matches any request and extracts a `Future` of
+/// the request body, transparently decompressed according to its
+/// `Content-Encoding` header (`gzip` or `deflate`; anything else, including
+/// a missing header, is passed through unchanged).
+///
+/// `limit` bounds the *decoded* size, failing with `BodyError::TooLarge`
+/// once exceeded. This is unrelated to, and not covered by,
+/// `content_length_limit()`, which only bounds the compressed bytes read
+/// off the wire — a small, highly-compressible body (a "zip bomb") can
+/// expand to an enormous decoded size without ever exceeding a limit on
+/// the wire bytes, so callers must pass a `limit` here too.
+///
+/// The decoded body is handed back as bytes. `json()` and `form()` each
+/// take the body themselves, so they cannot be chained after this filter;
+/// decode the bytes in the handler instead:
+///
+/// ```
+/// # extern crate serde_json;
+/// # extern crate warp;
+/// use warp::{Filter, Future};
+///
+/// # fn main() {
+/// let upload = warp::body::decompressed(1024 * 16)
+///     .map(|body: warp::body::DecompressedFut| {
+///         Future::map(body, |bytes: Vec<u8>| {
+///             serde_json::from_slice::<serde_json::Value>(&bytes)
+///         })
+///     });
+/// # }
+/// ```
+pub fn decompressed(limit: u64) -> impl Filter<Extract=Cons<DecompressedFut>> + Copy {
+    filter_fn_cons(move || {
+        route::with(move |route| {
+            let decoder = route.headers()
+                .get(CONTENT_ENCODING)
+                .and_then(|val| val.to_str().ok())
+                .map(Decoder::for_encoding)
+                .unwrap_or(Decoder::Identity);
+            route.take_body()
+                .map(|body| DecompressedFut {
+                    body: body.unwrap(),
+                    decoder,
+                    out: Vec::new(),
+                    limit,
+                })
+        })
+    })
+}
+
+impl From<::hyper::Error> for BodyError {
+    fn from(e: ::hyper::Error) -> BodyError {
+        debug!("concat error: {}", e);
+        BodyError::Io(e)
+    }
+}
+
 /// dox?
-pub struct ConcatFut {
-    fut: Concat2<Body>,
+pub struct ConcatFut<S = Body>
+where
+    S: Stream<Item=Chunk>,
+{
+    fut: Concat2<S>,
 }
 
-impl Future for ConcatFut {
+impl<S> Future for ConcatFut<S>
+where
+    S: Stream<Item=Chunk>,
+    S::Error: Into<BodyError>,
+{
     type Item = Chunk;
-    type Error = Error;
+    type Error = BodyError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        self.fut.poll()
-            .map_err(|e| {
-                debug!("concat error: {}", e);
-                Error(())
-            })
+        self.fut.poll().map_err(Into::into)
+    }
+}
+
+/// A `Body` that fails with `BodyError::TooLarge` once more than `limit`
+/// bytes have been read off of it, regardless of what `Content-Length`
+/// (if anything) the request declared.
+///
+/// `too_large` lets `content_length_limit()` pre-condemn a body whose
+/// *declared* `Content-Length` already exceeded the limit, so that case
+/// surfaces the same `BodyError::TooLarge` as an overflow discovered
+/// mid-stream, instead of a bare "filter didn't match".
+pub struct LimitedBody {
+    body: Body,
+    read: u64,
+    limit: u64,
+    too_large: bool,
+}
+
+impl Stream for LimitedBody {
+    type Item = Chunk;
+    type Error = BodyError;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.too_large {
+            debug!("content-length exceeds limit ({})", self.limit);
+            return Err(BodyError::TooLarge);
+        }
+        match try_ready!(self.body.poll().map_err(BodyError::from)) {
+            Some(chunk) => {
+                self.read += chunk.len() as u64;
+                if self.read > self.limit {
+                    debug!("body ({} bytes so far) exceeds content_length_limit ({})", self.read, self.limit);
+                    return Err(BodyError::TooLarge);
+                }
+                Ok(Async::Ready(Some(chunk)))
+            }
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+
+/// A `Read`-only buffer fed by incoming `Chunk`s.
+///
+/// `Ok(0)` from `Read::read` means true EOF, so an empty buffer instead
+/// returns `WouldBlock` — "no data available *yet*" — unless `finish()`
+/// has been called to mark the stream as actually over. This is the
+/// contract `libflate`'s non-blocking decoders expect from their inner
+/// reader.
+#[derive(Default)]
+struct ChunkBuf {
+    buf: VecDeque<u8>,
+    eof: bool,
+}
+
+impl ChunkBuf {
+    fn extend(&mut self, chunk: &[u8]) {
+        self.buf.extend(chunk.iter().cloned());
+    }
+
+    fn finish(&mut self) {
+        self.eof = true;
+    }
+}
+
+impl Read for ChunkBuf {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.buf.is_empty() {
+            if self.eof {
+                return Ok(0);
+            }
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = ::std::cmp::min(out.len(), self.buf.len());
+        for slot in out[..n].iter_mut() {
+            *slot = self.buf.pop_front().expect("n <= self.buf.len()");
+        }
+        Ok(n)
+    }
+}
+
+enum Decoder {
+    Identity,
+    Gzip(gzip::Decoder<ChunkBuf>),
+    Deflate(deflate::Decoder<ChunkBuf>),
+}
+
+impl Decoder {
+    fn for_encoding(encoding: &str) -> Decoder {
+        match encoding.trim().to_ascii_lowercase().as_str() {
+            "gzip" => Decoder::Gzip(gzip::Decoder::new(ChunkBuf::default())),
+            "deflate" => Decoder::Deflate(deflate::Decoder::new(ChunkBuf::default())),
+            _ => Decoder::Identity,
+        }
+    }
+
+    /// Feeds a chunk of compressed input into the decoder.
+    fn feed(&mut self, chunk: &[u8]) {
+        match *self {
+            Decoder::Identity => {},
+            Decoder::Gzip(ref mut d) => d.as_inner_mut().extend(chunk),
+            Decoder::Deflate(ref mut d) => d.as_inner_mut().extend(chunk),
+        }
+    }
+
+    /// Marks the input as finished, so the inner reader's next empty read
+    /// is reported as true EOF instead of `WouldBlock`.
+    fn finish(&mut self) {
+        match *self {
+            Decoder::Identity => {},
+            Decoder::Gzip(ref mut d) => d.as_inner_mut().finish(),
+            Decoder::Deflate(ref mut d) => d.as_inner_mut().finish(),
+        }
+    }
+
+    /// Drains whatever decoded output is available right now into `out`.
+    ///
+    /// A single fed chunk may yield zero, one, or many calls' worth of
+    /// decoded output, so this keeps reading until the decoder reports
+    /// either that nothing more is ready yet (`WouldBlock`, not an error)
+    /// or that the compressed stream has truly ended (`Ok(0)`).
+    fn drain_into(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        let mut buf = [0u8; 8 * 1024];
+        loop {
+            let n = match *self {
+                Decoder::Identity => return Ok(()),
+                Decoder::Gzip(ref mut d) => d.read(&mut buf),
+                Decoder::Deflate(ref mut d) => d.read(&mut buf),
+            };
+            let n = match n {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                return Ok(());
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+    }
+}
+
+/// The `Future` returned by `decompressed()`.
+pub struct DecompressedFut {
+    body: Body,
+    decoder: Decoder,
+    out: Vec<u8>,
+    limit: u64,
+}
+
+impl DecompressedFut {
+    /// Fails once the *decoded* output has grown past `limit`, which is
+    /// what actually bounds memory use for a highly-compressible body —
+    /// `content_length_limit()` only ever sees the much smaller wire bytes.
+    fn check_limit(&self) -> Result<(), BodyError> {
+        if self.out.len() as u64 > self.limit {
+            debug!("decompressed body ({} bytes so far) exceeds limit ({})", self.out.len(), self.limit);
+            return Err(BodyError::TooLarge);
+        }
+        Ok(())
+    }
+}
+
+impl Future for DecompressedFut {
+    type Item = Vec<u8>;
+    type Error = BodyError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match try_ready!(self.body.poll().map_err(BodyError::Io)) {
+                Some(chunk) => {
+                    match self.decoder {
+                        Decoder::Identity => self.out.extend_from_slice(&chunk),
+                        _ => {
+                            self.decoder.feed(&chunk);
+                            self.decoder.drain_into(&mut self.out)
+                                .map_err(|e| BodyError::Deserialize(Box::new(e)))?;
+                        }
+                    }
+                    self.check_limit()?;
+                }
+                None => {
+                    self.decoder.finish();
+                    self.decoder.drain_into(&mut self.out)
+                        .map_err(|e| BodyError::Deserialize(Box::new(e)))?;
+                    self.check_limit()?;
+                    let out = ::std::mem::replace(&mut self.out, Vec::new());
+                    return Ok(Async::Ready(out));
+                }
+            }
+        }
+    }
+}
+
+/// dox?
+pub struct BytesFut {
+    concat: ConcatFut,
+}
+
+impl Future for BytesFut {
+    type Item = Vec<u8>;
+    type Error = BodyError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let chunk = try_ready!(self.concat.poll());
+        Ok(Async::Ready(chunk.to_vec()))
     }
 }
 
@@ -76,7 +545,7 @@ where
     T: DeserializeOwned,
 {
     type Item = T;
-    type Error = Error;
+    type Error = BodyError;
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         let buf = try_ready!(self.concat.poll());
@@ -84,8 +553,132 @@ where
             Ok(val) => Ok(Async::Ready(val)),
             Err(err) => {
                 debug!("request json body error: {}", err);
-                Err(Error(()))
+                Err(BodyError::Deserialize(Box::new(err)))
             }
         }
     }
 }
+
+/// The `Future` returned by `form()`.
+pub struct FormFut<T> {
+    concat: ConcatFut,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Future for FormFut<T>
+where
+    T: DeserializeOwned,
+{
+    type Item = T;
+    type Error = BodyError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let buf = try_ready!(self.concat.poll());
+        match serde_urlencoded::from_bytes(&buf) {
+            Ok(val) => Ok(Async::Ready(val)),
+            Err(err) => {
+                debug!("request form body error: {}", err);
+                Err(BodyError::Deserialize(Box::new(err)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_buf_read_would_block_before_finish() {
+        let mut buf = ChunkBuf::default();
+        let mut out = [0u8; 4];
+        let err = buf.read(&mut out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn chunk_buf_read_is_eof_after_finish() {
+        let mut buf = ChunkBuf::default();
+        buf.finish();
+        let mut out = [0u8; 4];
+        assert_eq!(buf.read(&mut out).unwrap(), 0);
+    }
+
+    #[test]
+    fn chunk_buf_read_drains_extended_bytes() {
+        let mut buf = ChunkBuf::default();
+        buf.extend(b"hello");
+
+        let mut out = [0u8; 3];
+        assert_eq!(buf.read(&mut out).unwrap(), 3);
+        assert_eq!(&out, b"hel");
+
+        let mut out = [0u8; 3];
+        assert_eq!(buf.read(&mut out).unwrap(), 2);
+        assert_eq!(&out[..2], b"lo");
+    }
+
+    #[test]
+    fn decoder_for_encoding_is_case_insensitive() {
+        assert!(match Decoder::for_encoding("GZIP") {
+            Decoder::Gzip(_) => true,
+            _ => false,
+        });
+        assert!(match Decoder::for_encoding(" Deflate ") {
+            Decoder::Deflate(_) => true,
+            _ => false,
+        });
+        assert!(match Decoder::for_encoding("identity") {
+            Decoder::Identity => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn limited_body_passes_through_under_limit() {
+        let body = LimitedBody {
+            body: Body::from("hello"),
+            read: 0,
+            limit: 10,
+            too_large: false,
+        };
+
+        let chunks = body.wait().collect::<Result<Vec<_>, _>>().unwrap();
+        let total: usize = chunks.iter().map(|c| c.len()).sum();
+        assert_eq!(total, 5);
+    }
+
+    #[test]
+    fn limited_body_fails_mid_stream_once_over_limit() {
+        let body = LimitedBody {
+            body: Body::from("hello world"),
+            read: 0,
+            limit: 5,
+            too_large: false,
+        };
+
+        let err = body.wait().collect::<Result<Vec<_>, _>>().unwrap_err();
+        assert!(match err {
+            BodyError::TooLarge => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn limited_body_fails_immediately_when_declared_length_too_large() {
+        let mut body = LimitedBody {
+            body: Body::from("hello"),
+            read: 0,
+            limit: 1,
+            too_large: true,
+        };
+
+        // Must fail without ever touching the underlying body stream.
+        let err = body.poll().unwrap_err();
+        assert!(match err {
+            BodyError::TooLarge => true,
+            _ => false,
+        });
+        assert_eq!(body.read, 0);
+    }
+}